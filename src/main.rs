@@ -1,5 +1,9 @@
 use app_dirs;
 use failure;
+use humantime;
+use notify_rust;
+use rodio;
+use serde_cbor;
 use structopt;
 use toml;
 
@@ -9,7 +13,9 @@ use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
-use std::path::PathBuf;
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
@@ -18,26 +24,96 @@ const APP_INFO: AppInfo = AppInfo {
     author: "Douglas Campos <qmx@qmx.me>",
 };
 
+fn now() -> Result<u64, failure::Error> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn socket_path() -> Result<PathBuf, failure::Error> {
+    Ok(app_dirs::app_dir(AppDataType::UserData, &APP_INFO, "")?.join("marinara.sock"))
+}
+
+fn send_command(command: &Command) -> Result<Option<Answer>, failure::Error> {
+    match UnixStream::connect(socket_path()?) {
+        Ok(stream) => {
+            serde_cbor::to_writer(&stream, command)?;
+            stream.shutdown(Shutdown::Write)?;
+            Ok(Some(serde_cbor::from_reader(&stream)?))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "marinara", about = "pomodoro timer")]
 enum Marinara {
     #[structopt(name = "start", about = "start a new pomodoro")]
-    Start {},
+    Start {
+        #[structopt(long, parse(try_from_str = parse_duration))]
+        work: Option<Duration>,
+        #[structopt(long, parse(try_from_str = parse_duration))]
+        rest: Option<Duration>,
+    },
     #[structopt(name = "stop", about = "stop current pomodoro")]
     Stop {},
     #[structopt(name = "status", about = "current pomodoro status")]
     Status {},
+    #[structopt(name = "watch", about = "run a resident daemon with a live countdown")]
+    Watch {},
+    #[structopt(name = "toggle", about = "pause or resume the current pomodoro")]
+    Toggle {},
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Command {
+    Start {
+        work_secs: Option<i64>,
+        rest_secs: Option<i64>,
+    },
+    Stop,
+    Status,
+    Toggle,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Answer {
+    status: String,
 }
 
 #[derive(Debug)]
 struct Config {
     duration: Duration,
     rest: Duration,
+    long_rest: Duration,
+    cycles_till_long: u64,
+    sound_file: Option<PathBuf>,
 }
 
 impl Config {
-    fn total(&self) -> Duration {
-        self.duration + self.rest
+    fn config_path() -> Result<PathBuf, failure::Error> {
+        Ok(app_dirs::app_dir(AppDataType::UserData, &APP_INFO, "")?.join("settings.toml"))
+    }
+
+    fn load() -> Result<Config, failure::Error> {
+        match File::open(&Config::config_path()?) {
+            Ok(mut file) => {
+                let mut toml = String::new();
+                file.read_to_string(&mut toml)?;
+                let raw: RawConfig = toml::from_str(&toml)?;
+                if raw.cycles_till_long == 0 {
+                    return Err(failure::err_msg(
+                        "cycles_till_long must be greater than zero",
+                    ));
+                }
+                Ok(Config {
+                    duration: parse_duration(&raw.work_time)?,
+                    rest: parse_duration(&raw.short_break)?,
+                    long_rest: parse_duration(&raw.long_break)?,
+                    cycles_till_long: raw.cycles_till_long,
+                    sound_file: raw.sound_file,
+                })
+            }
+            Err(_) => Ok(Default::default()),
+        }
     }
 }
 
@@ -46,13 +122,52 @@ impl Default for Config {
         Config {
             duration: Duration::minutes(25),
             rest: Duration::minutes(5),
+            long_rest: Duration::minutes(15),
+            cycles_till_long: 4,
+            sound_file: None,
         }
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct RawConfig {
+    work_time: String,
+    short_break: String,
+    #[serde(default = "default_long_break")]
+    long_break: String,
+    #[serde(default = "default_cycles_till_long")]
+    cycles_till_long: u64,
+    #[serde(default)]
+    sound_file: Option<PathBuf>,
+}
+
+fn default_long_break() -> String {
+    "15m".to_string()
+}
+
+fn default_cycles_till_long() -> u64 {
+    4
+}
+
+fn parse_duration(value: &str) -> Result<Duration, failure::Error> {
+    Ok(Duration::from_std(humantime::parse_duration(value)?)?)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct State {
     started_at: Option<u64>,
+    #[serde(default)]
+    completed: u64,
+    #[serde(default)]
+    last_phase: Option<String>,
+    #[serde(default)]
+    paused_at: Option<u64>,
+    #[serde(default)]
+    accumulated_pause: u64,
+    #[serde(default)]
+    work_secs: Option<i64>,
+    #[serde(default)]
+    rest_secs: Option<i64>,
     #[serde(skip)]
     config: Config,
 }
@@ -61,6 +176,12 @@ impl Default for State {
     fn default() -> State {
         State {
             started_at: None,
+            completed: 0,
+            last_phase: None,
+            paused_at: None,
+            accumulated_pause: 0,
+            work_secs: None,
+            rest_secs: None,
             config: Default::default(),
         }
     }
@@ -92,22 +213,78 @@ impl State {
         Ok(())
     }
 
-    fn reset(&mut self) -> Result<(), failure::Error> {
+    fn reset(&mut self, current_time: u64) -> Result<(), failure::Error> {
+        if let Some(Pomodoro::Done) = self.pomodoro(current_time) {
+            self.mark_completed();
+        }
         self.started_at = None;
+        self.paused_at = None;
+        self.accumulated_pause = 0;
+        Ok(self.save()?)
+    }
+
+    fn mark_completed(&mut self) {
+        if self.last_phase.as_deref() != Some("done") {
+            self.completed += 1;
+        }
+    }
+
+    fn toggle(&mut self, current_time: u64) -> Result<(), failure::Error> {
+        if self.started_at.is_none() {
+            return Ok(());
+        }
+        match self.paused_at {
+            Some(paused_at) => {
+                self.accumulated_pause += current_time.saturating_sub(paused_at);
+                self.paused_at = None;
+            }
+            None => self.paused_at = Some(current_time),
+        }
         Ok(self.save()?)
     }
 
+    fn render(&self, pomodoro: Option<Pomodoro>) -> String {
+        let rendered = pomodoro.display();
+        if self.paused_at.is_some() {
+            format!("P{}", rendered)
+        } else {
+            rendered
+        }
+    }
+
+    fn is_long_cycle(&self) -> bool {
+        self.completed % self.config.cycles_till_long == self.config.cycles_till_long - 1
+    }
+
     fn pomodoro(&self, current_time: u64) -> Option<Pomodoro> {
         if let Some(started_at) = self.started_at {
-            let elapsed: i64 = (current_time - started_at) as i64;
-            if elapsed <= self.config.duration.num_seconds() {
+            let pause = self.accumulated_pause
+                + self
+                    .paused_at
+                    .map_or(0, |paused_at| current_time.saturating_sub(paused_at));
+            let elapsed: i64 = current_time
+                .saturating_sub(started_at)
+                .saturating_sub(pause) as i64;
+            let duration = self
+                .work_secs
+                .map_or(self.config.duration, Duration::seconds);
+            let rest = if self.is_long_cycle() {
+                self.config.long_rest
+            } else {
+                self.rest_secs.map_or(self.config.rest, Duration::seconds)
+            };
+            let total = duration + rest;
+            if elapsed <= duration.num_seconds() {
                 Some(Pomodoro::Work {
-                    remaining_time: self.config.duration - Duration::seconds(elapsed),
-                })
-            } else if elapsed < self.config.total().num_seconds() {
-                Some(Pomodoro::Rest {
-                    remaining_time: self.config.total() - Duration::seconds(elapsed),
+                    remaining_time: duration - Duration::seconds(elapsed),
                 })
+            } else if elapsed < total.num_seconds() {
+                let remaining_time = total - Duration::seconds(elapsed);
+                if self.is_long_cycle() {
+                    Some(Pomodoro::LongRest { remaining_time })
+                } else {
+                    Some(Pomodoro::Rest { remaining_time })
+                }
             } else {
                 Some(Pomodoro::Done)
             }
@@ -115,6 +292,59 @@ impl State {
             None
         }
     }
+
+    fn phase_name(pomodoro: &Option<Pomodoro>) -> Option<String> {
+        pomodoro.as_ref().map(|pomodoro| {
+            match pomodoro {
+                Pomodoro::Work { .. } => "work",
+                Pomodoro::Rest { .. } => "rest",
+                Pomodoro::LongRest { .. } => "long_rest",
+                Pomodoro::Done => "done",
+            }
+            .to_string()
+        })
+    }
+
+    fn notify(&mut self, pomodoro: &Option<Pomodoro>) -> Result<(), failure::Error> {
+        let phase = State::phase_name(pomodoro);
+        if phase == self.last_phase {
+            return Ok(());
+        }
+        match phase.as_ref().map(String::as_str) {
+            Some("rest") | Some("long_rest") => {
+                let _ = notify_rust::Notification::new()
+                    .summary("Break time!")
+                    .body("Your pomodoro is done, take a break.")
+                    .show();
+            }
+            Some("work") => {
+                let _ = notify_rust::Notification::new()
+                    .summary("Back to work!")
+                    .body("Break's over.")
+                    .show();
+            }
+            Some("done") => {
+                self.mark_completed();
+                if let Some(sound_file) = &self.config.sound_file {
+                    let _ = State::play_sound(sound_file);
+                }
+            }
+            _ => {}
+        }
+        self.last_phase = phase;
+        self.save()?;
+        Ok(())
+    }
+
+    fn play_sound(sound_file: &Path) -> Result<(), failure::Error> {
+        let (_stream, stream_handle) = rodio::OutputStream::try_default()?;
+        let sink = rodio::Sink::try_new(&stream_handle)?;
+        let file = File::open(sound_file)?;
+        let source = rodio::Decoder::new(std::io::BufReader::new(file))?;
+        sink.append(source);
+        sink.sleep_until_end();
+        Ok(())
+    }
 }
 
 trait Display {
@@ -130,10 +360,11 @@ impl Display for Option<Pomodoro> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Pomodoro {
     Work { remaining_time: Duration },
     Rest { remaining_time: Duration },
+    LongRest { remaining_time: Duration },
     Done,
 }
 
@@ -142,13 +373,16 @@ impl Pomodoro {
         match self {
             Pomodoro::Work { .. } => "W",
             Pomodoro::Rest { .. } => "R",
+            Pomodoro::LongRest { .. } => "L",
             Pomodoro::Done => ">",
         }
     }
 
     fn display(self) -> String {
         match self {
-            Pomodoro::Work { remaining_time } | Pomodoro::Rest { remaining_time } => {
+            Pomodoro::Work { remaining_time }
+            | Pomodoro::Rest { remaining_time }
+            | Pomodoro::LongRest { remaining_time } => {
                 if remaining_time.num_minutes() > 0 {
                     format!("{}:{:2}m", self.prefix(), remaining_time.num_minutes())
                 } else {
@@ -190,27 +424,166 @@ fn test_pomodoro_display() {
         .display(),
         "R: 3m"
     );
+    assert_eq!(
+        Pomodoro::LongRest {
+            remaining_time: Duration::minutes(15)
+        }
+        .display(),
+        "L:15m"
+    );
     assert_eq!(Pomodoro::Done {}.display(), ">DONE");
 }
 
+#[test]
+fn test_is_long_cycle() {
+    let mut state = State::default();
+    state.config.cycles_till_long = 4;
+    state.completed = 3;
+    assert!(state.is_long_cycle());
+    state.completed = 2;
+    assert!(!state.is_long_cycle());
+}
+
+#[test]
+fn test_pomodoro_respects_custom_durations() {
+    let mut state = State::default();
+    state.started_at = Some(0);
+    state.work_secs = Some(60);
+    state.rest_secs = Some(30);
+    match state.pomodoro(30) {
+        Some(Pomodoro::Work { remaining_time }) => assert_eq!(remaining_time.num_seconds(), 30),
+        other => panic!("unexpected {:?}", other),
+    }
+    match state.pomodoro(70) {
+        Some(Pomodoro::Rest { remaining_time }) => assert_eq!(remaining_time.num_seconds(), 20),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_pomodoro_freezes_elapsed_while_paused() {
+    let mut state = State::default();
+    state.started_at = Some(0);
+    state.paused_at = Some(100);
+    let remaining_at_pause = match state.pomodoro(100) {
+        Some(Pomodoro::Work { remaining_time }) => remaining_time,
+        other => panic!("unexpected {:?}", other),
+    };
+    let remaining_later = match state.pomodoro(500) {
+        Some(Pomodoro::Work { remaining_time }) => remaining_time,
+        other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(remaining_at_pause, remaining_later);
+}
+
+#[test]
+fn test_toggle_pauses_and_resumes() {
+    let mut state = State::default();
+    state.started_at = Some(0);
+    state.toggle(100).unwrap();
+    assert_eq!(state.paused_at, Some(100));
+    state.toggle(150).unwrap();
+    assert_eq!(state.paused_at, None);
+    assert_eq!(state.accumulated_pause, 50);
+}
+
+#[test]
+fn test_raw_config_defaults_when_fields_missing() {
+    let raw: RawConfig = toml::from_str("work_time = \"25m\"\nshort_break = \"5m\"\n").unwrap();
+    assert_eq!(raw.long_break, "15m");
+    assert_eq!(raw.cycles_till_long, 4);
+    assert_eq!(raw.sound_file, None);
+}
+
+fn handle_connection(stream: UnixStream, state: &mut State) -> Result<(), failure::Error> {
+    let command: Command = serde_cbor::from_reader(&stream)?;
+    match command {
+        Command::Start { work_secs, rest_secs } => {
+            state.started_at = Some(now()?);
+            state.work_secs = work_secs;
+            state.rest_secs = rest_secs;
+            state.paused_at = None;
+            state.accumulated_pause = 0;
+            state.save()?;
+        }
+        Command::Stop => state.reset(now()?)?,
+        Command::Status => {}
+        Command::Toggle => state.toggle(now()?)?,
+    }
+    let answer = Answer {
+        status: state.render(state.pomodoro(now()?)),
+    };
+    serde_cbor::to_writer(&stream, &answer)?;
+    stream.shutdown(Shutdown::Write)?;
+    Ok(())
+}
+
+fn watch() -> Result<(), failure::Error> {
+    let socket_path = socket_path()?;
+    let _ = std::fs::remove_file(&socket_path);
+    let listener = UnixListener::bind(&socket_path)?;
+    listener.set_nonblocking(true)?;
+    let mut state = State::load(Config::load()?)?;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => handle_connection(stream, &mut state)?,
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+        let pomodoro = state.pomodoro(now()?);
+        state.notify(&pomodoro)?;
+        print!("\r{}", state.render(pomodoro));
+        std::io::stdout().flush()?;
+        std::thread::sleep(std::time::Duration::from_millis(300));
+    }
+}
+
 fn main() -> Result<(), failure::Error> {
     let opt = Marinara::from_args();
     match opt {
-        Marinara::Start {} => {
-            let config: Config = Default::default();
-            let state = State {
-                started_at: Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
-                config,
-            };
-            state.save()?;
+        Marinara::Start { work, rest } => {
+            let work_secs = work.map(|duration| duration.num_seconds());
+            let rest_secs = rest.map(|duration| duration.num_seconds());
+            if let Some(answer) = send_command(&Command::Start { work_secs, rest_secs })? {
+                println!("{}", answer.status);
+            } else {
+                let mut state = State::load(Config::load()?)?;
+                state.started_at = Some(now()?);
+                state.work_secs = work_secs;
+                state.rest_secs = rest_secs;
+                state.paused_at = None;
+                state.accumulated_pause = 0;
+                state.save()?;
+            }
         }
         Marinara::Stop {} => {
-            State::load(Default::default())?.reset()?;
+            if let Some(answer) = send_command(&Command::Stop)? {
+                println!("{}", answer.status);
+            } else {
+                let mut state = State::load(Config::load()?)?;
+                state.reset(now()?)?;
+            }
         }
         Marinara::Status {} => {
-            let state = State::load(Default::default())?;
-            let current_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-            println!("{}", state.pomodoro(current_time).display());
+            if let Some(answer) = send_command(&Command::Status)? {
+                println!("{}", answer.status);
+            } else {
+                let mut state = State::load(Config::load()?)?;
+                let pomodoro = state.pomodoro(now()?);
+                state.notify(&pomodoro)?;
+                println!("{}", state.render(pomodoro));
+            }
+        }
+        Marinara::Watch {} => watch()?,
+        Marinara::Toggle {} => {
+            if let Some(answer) = send_command(&Command::Toggle)? {
+                println!("{}", answer.status);
+            } else {
+                let mut state = State::load(Config::load()?)?;
+                state.toggle(now()?)?;
+                let pomodoro = state.pomodoro(now()?);
+                println!("{}", state.render(pomodoro));
+            }
         }
     };
     Ok(())